@@ -1,10 +1,69 @@
-use std::fs;
+use std::{fs, io, thread};
 use std::io::Write;
-use std::path::Path;
-use ndarray::s;
-use subprocess::{Popen, PopenConfig, Redirection};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SendError, SyncSender};
+use std::thread::JoinHandle;
+use ndarray::{s, Zip};
+use subprocess::{Popen, PopenConfig, PopenError, Redirection};
 use crate::entity::Entity;
+use crate::error::FerrociousError;
 use crate::mutator::timestamp::TimeStamp;
+use crate::utils::defaults::DEFAULT_FRAME_QUEUE_DEPTH;
+
+/// Number ffmpeg gives the first file of a PNG sequence.
+const PNG_SEQUENCE_START_NUMBER: u32 = 1;
+
+/// Spawns a thread that writes every frame sent over the returned channel to `sink`, in order,
+/// so rendering doesn't block on ffmpeg. Dropping the sender ends the thread, which hands `sink` back.
+pub(crate) fn spawn_frame_writer<W: Write + Send + 'static>(mut sink: W) -> (SyncSender<Vec<u8>>, JoinHandle<io::Result<W>>) {
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(DEFAULT_FRAME_QUEUE_DEPTH);
+    let handle = thread::spawn(move || {
+        for frame in receiver {
+            sink.write_all(&frame)?;
+        }
+        sink.flush()?;
+        Ok(sink)
+    });
+    (sender, handle)
+}
+
+/// Composites `src` over `dst` using standard source-over alpha blending. Both are packed RGBA.
+pub fn blend_over(src: u32, dst: u32) -> u32 {
+    let src_alpha = (src & 0xFF) as f32 / 255.0;
+    let dst_alpha = (dst & 0xFF) as f32 / 255.0;
+    let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+    if out_alpha <= 0.0 {
+        return 0;
+    }
+
+    let channel = |shift: u32| {
+        let src_channel = ((src >> shift) & 0xFF) as f32;
+        let dst_channel = ((dst >> shift) & 0xFF) as f32;
+        let blended = (src_channel * src_alpha + dst_channel * dst_alpha * (1.0 - src_alpha)) / out_alpha;
+        (blended.round() as u32) << shift
+    };
+
+    channel(24) | channel(16) | channel(8) | (out_alpha * 255.0).round() as u32
+}
+
+/// Builds an ffmpeg invocation that reads raw rgba frames from stdin. `output_args` pick the
+/// codec, muxer and destination.
+pub(crate) fn ffmpeg_command(width: u32, height: u32, fps: u32, output_args: &[&str]) -> Vec<String> {
+    let ffmpeg_bin: &str =  if std::env::consts::OS == "windows" { "ffmpeg.exe" } else { "ffmpeg" };
+
+    let input_args = [
+        ffmpeg_bin,
+        "-y",  // overwrite output file if it exists
+        "-f", "rawvideo",
+        "-s", &(width.to_string() + "x" + height.to_string().as_str()),  // size of one frame
+        "-pix_fmt", "rgba", //
+        "-r", &fps.to_string(),  // frame rate
+        "-i", "-",  // The input comes from a pipe
+        "-loglevel", "error",
+    ];
+
+    input_args.iter().chain(output_args).map(|arg| arg.to_string()).collect()
+}
 
 pub trait Canvas {
     fn construct(&self);
@@ -12,80 +71,238 @@ pub trait Canvas {
     fn get_fps(&self) -> u32;
     fn get_entities(&self) -> Vec<impl Entity>;
     fn get_background(&self) -> ndarray::Array2<u32>;
+
+    /// Called once for every frame handed to the encoder, with the frame's absolute timestamp, its
+    /// 0-based index within the range being rendered, and the total number of frames in that range.
+    /// Override it to drive a progress bar from `index + 1` out of `total_frames`.
+    fn on_frame_rendered(&self, _current: &TimeStamp, _index: u32, _total_frames: u32) {}
+
     fn unmask(rgba: u32) -> [u8; 4] {
         [
             ((rgba & 0xFF000000) >> 24) as u8,
-            ((rgba & 0x00FF0000) >> 24) as u8,
-            ((rgba & 0x0000FF00) >> 24) as u8,
-            ((rgba & 0x000000FF)>> 24) as u8,
+            ((rgba & 0x00FF0000) >> 16) as u8,
+            ((rgba & 0x0000FF00) >> 8) as u8,
+            (rgba & 0x000000FF) as u8,
         ]
     }
 
+    /// Serializes a frame for ffmpeg's rawvideo input. Frames are indexed `[x, y]`, but ffmpeg
+    /// reads pixels row by row, so this walks the transpose.
+    fn frame_to_bytes(frame: &ndarray::Array2<u32>) -> Vec<u8> {
+        frame.t().iter().flat_map(|&val| Self::unmask(val).into_iter()).collect()
+    }
 
-    fn launch_writing_subprocess(width: u32, height: u32, fps: u32, end_dir: &str, name: &str) -> Popen {
-
-        let FFMPEG_BIN: &str =  if std::env::consts::OS == "windows" { "ffmpeg" } else { "ffmpeg.exe" };
-
-        let command = [
-            FFMPEG_BIN,
-            "-y",  // overwrite output file if it exists
-            "-f", "rawvideo",
-            "-s", &(width.to_string() + "x" + height.to_string().as_str()),  // size of one frame
-            "-pix_fmt", "rgba", //
-            "-r", &fps.to_string(),  // frame rate
-            "-i", "-",  // The input comes from a pipe
-            "-an",  // Tells FFMPEG not to expect any audio
-            "-loglevel", "error",
-            "-vcodec", "libx264",
-            &(end_dir.to_owned() + "/" + name)
-        ];
 
-        Popen::create(&command, PopenConfig {
+    fn launch_writing_subprocess(width: u32, height: u32, fps: u32, output_args: &[&str]) -> Result<Popen, PopenError> {
+        Popen::create(&ffmpeg_command(width, height, fps, output_args), PopenConfig {
             stdin: Redirection::Pipe,
             ..Default::default()
-        }).expect("We should be able to make a pipe")
+        })
+    }
+
+    fn render_frame(&self, current_frame: &TimeStamp) -> ndarray::Array2<u32> {
+        let fps: u32 = self.get_fps();
+        let (width, height): (u32, u32) = self.get_width_and_height();
+        let mut frame = self.get_background();
+
+        // stable, so entities with equal z-indices keep their get_entities() order
+        let mut entities = self.get_entities();
+        entities.sort_by_key(|entity| entity.z_index());
+
+        for entity in &mut entities {
+            if !entity.is_active_at(current_frame) {
+                continue;
+            }
+
+            entity.tick(current_frame);
+            let (upper_left_x, upper_left_y)= entity.upper_left_coords();
+            if upper_left_x >= width || upper_left_y >= height {
+                continue;
+            }
+
+            let (size_x, size_y) = entity.get_size();
+            let entity_render = entity.render(current_frame, fps);
+            let end_x = width.min(upper_left_x + size_x);
+            let end_y = height.min(upper_left_y + size_y);
+            let mut region = frame.slice_mut(s![upper_left_x as i32..end_x as i32, upper_left_y as i32..end_y as i32]);
+            let source = entity_render.slice(s![..(end_x - upper_left_x) as i32, ..(end_y - upper_left_y) as i32]);
+
+            if let Some(cutoff) = entity.alpha_cutoff() {
+                let threshold = (cutoff.clamp(0.0, 1.0) * 255.0).round() as u32;
+                Zip::from(&mut region).and(&source).for_each(|dst, &src| {
+                    if src & 0xFF >= threshold {
+                        *dst = src | 0xFF;
+                    }
+                });
+            } else if entity.get_blending() {
+                Zip::from(&mut region).and(&source).for_each(|dst, &src| *dst = blend_over(src, *dst));
+            } else {
+                region.assign(&source);
+            }
+        }
+
+        frame
     }
 
-    fn save(&self, end_dir: &str, name: &str, end: TimeStamp) {
-        println!("Starting write");
+    /// Renders every frame in `[start, end)`, handing each to `write_frame` in order and reporting
+    /// progress through `on_frame_rendered`. Stops at the first error from `write_frame`.
+    fn render_timeline<E>(&self, start: &TimeStamp, end: &TimeStamp, mut write_frame: impl FnMut(ndarray::Array2<u32>) -> Result<(), E>) -> Result<(), E> {
+        let fps = self.get_fps();
+        let (first_frame, end_frame) = (start.as_num_frames(fps), end.as_num_frames(fps));
+        let total_frames = end_frame.saturating_sub(first_frame);
 
-        if !Path::new(end_dir).exists() {
-            fs::create_dir_all(end_dir).expect("Should be able to make directory");
+        for frame_number in first_frame..end_frame {
+            let current_frame = TimeStamp::from_frames(frame_number, fps);
+            write_frame(self.render_frame(&current_frame))?;
+            self.on_frame_rendered(&current_frame, frame_number - first_frame, total_frames);
         }
 
-        let FPS: u32 = self.get_fps();
-        let (WIDTH, HEIGHT): (u32, u32) = self.get_width_and_height();
-
-        let mut process = Self::launch_writing_subprocess(WIDTH, HEIGHT, self.get_fps(), end_dir, name);
-        let mut current_frame = TimeStamp::new(None, None, None);
-
-        while current_frame < end {
-            let mut frame = self.get_background();
-            println!("processing frame {}", current_frame);
-            current_frame.increment();
-            for entity in &mut self.get_entities() {
-                if !entity.is_active_at(&current_frame) {
-                    continue;
-                }
-
-                entity.tick(&current_frame);
-                let (upper_left_x, upper_left_y)= entity.upper_left_coords();
-                let (size_x, size_y) = entity.get_size();
-                let entity_render = entity.render(&current_frame, FPS);
-                let end_x: i32 = if WIDTH < upper_left_x + size_x {WIDTH as i32} else {size_x as i32};
-                let end_y: i32 = if HEIGHT < upper_left_y + size_y {HEIGHT as i32} else {size_y as i32};
-                frame.slice_mut(s![upper_left_x as i32..end_x, upper_left_y as i32..end_y]).assign(&entity_render.slice(s![..end_x, ..end_y]));
-
-                let _ = &process.stdin.as_ref().expect("we should have stdin still").write(
-                    &frame.iter().map(|&val| Self::unmask(val).into_iter()).flatten().collect::<Vec<u8>>()
-                );
+        Ok(())
+    }
 
+    /// Like `render_timeline`, but renders up to `workers` frames at once on scoped threads. Every
+    /// frame gets its own `get_entities()` snapshot, so frames don't depend on each other, and they
+    /// still reach `write_frame` and `on_frame_rendered` in timestamp order. Falls back to
+    /// `render_timeline` if any entity reports itself impure.
+    fn render_timeline_parallel<E>(&self, start: &TimeStamp, end: &TimeStamp, workers: usize, mut write_frame: impl FnMut(ndarray::Array2<u32>) -> Result<(), E>) -> Result<(), E>
+    where
+        Self: Sync,
+    {
+        if !self.get_entities().iter().all(|entity| entity.is_pure()) {
+            return self.render_timeline(start, end, write_frame);
+        }
+
+        let fps = self.get_fps();
+        let (first_frame, end_frame) = (start.as_num_frames(fps), end.as_num_frames(fps));
+        let total_frames = end_frame.saturating_sub(first_frame);
+        let frame_numbers: Vec<u32> = (first_frame..end_frame).collect();
+
+        for batch in frame_numbers.chunks(workers.max(1)) {
+            let rendered: Vec<(u32, TimeStamp, ndarray::Array2<u32>)> = thread::scope(|scope| {
+                let handles: Vec<_> = batch.iter().map(|&frame_number| scope.spawn(move || {
+                    let current_frame = TimeStamp::from_frames(frame_number, fps);
+                    (frame_number, current_frame, self.render_frame(&current_frame))
+                })).collect();
+
+                handles.into_iter().map(|handle| handle.join().expect("a render worker shouldn't panic")).collect()
+            });
+
+            for (frame_number, current_frame, frame) in rendered {
+                write_frame(frame)?;
+                self.on_frame_rendered(&current_frame, frame_number - first_frame, total_frames);
             }
         }
 
-        let _ = process.stdin.as_ref().unwrap().sync_all();
-        process.wait().unwrap();
-        process.terminate().unwrap();
+        Ok(())
+    }
+
+    fn save(&self, end_dir: &str, name: &str, end: TimeStamp) -> Result<(), FerrociousError> {
+        self.save_range(end_dir, name, TimeStamp::zero(), end)
+    }
+
+    /// Saves only the frames in `[start, end)` as a standalone clip beginning at t=0. Entities
+    /// still see absolute timestamps, so their active ranges behave as they would in a full render.
+    fn save_range(&self, end_dir: &str, name: &str, start: TimeStamp, end: TimeStamp) -> Result<(), FerrociousError> {
+        let output = end_dir.to_owned() + "/" + name;
+        encode_range(self, end_dir, &video_output_args(&output), start, end)
+    }
+
+    /// Like `save`, but renders up to `workers` frames in parallel, or serially if any entity is impure.
+    fn save_parallel(&self, end_dir: &str, name: &str, end: TimeStamp, workers: usize) -> Result<(), FerrociousError>
+    where
+        Self: Sync,
+    {
+        let output = end_dir.to_owned() + "/" + name;
+        let start = TimeStamp::zero();
+        encode_with(self, end_dir, &video_output_args(&output), start, end, |write_frame| {
+            self.render_timeline_parallel(&start, &end, workers, write_frame)
+        })
+    }
+
+    /// Like `save`, but muxes the audio at `audio_path` into the output. Audio shorter than the
+    /// video just ends early; longer audio is cut off at the end of the video.
+    fn save_with_audio(&self, end_dir: &str, name: &str, end: TimeStamp, audio_path: &Path) -> Result<(), FerrociousError> {
+        if !audio_path.is_file() {
+            return Err(FerrociousError::Io(io::Error::new(io::ErrorKind::NotFound, format!("no audio file at {}", audio_path.display()))));
+        }
+
+        let output = end_dir.to_owned() + "/" + name;
+        let audio = audio_path.to_string_lossy();
+        let duration = format!("{:.3}", end.to_duration(self.get_fps()).as_secs_f64());
+        encode_range(self, end_dir, &[
+            "-i", &audio,
+            "-map", "0:v", "-map", "1:a",
+            "-vcodec", "libx264",
+            "-acodec", "aac",
+            "-t", &duration,  // trims audio that runs past the last frame
+            &output,
+        ], TimeStamp::zero(), end)
+    }
+
+    /// Saves every frame up to `end` as `<prefix>_0001.png`, `<prefix>_0002.png`, ... in `end_dir`,
+    /// keeping the alpha channel. Returns each frame's timestamp and file, in order.
+    fn save_png_sequence(&self, end_dir: &str, prefix: &str, end: TimeStamp) -> Result<Vec<(TimeStamp, PathBuf)>, FerrociousError> {
+        let pattern = end_dir.to_owned() + "/" + prefix + "_%04d.png";
+        let start_number = PNG_SEQUENCE_START_NUMBER.to_string();
+        encode_range(self, end_dir, &["-an", "-f", "image2", "-start_number", &start_number, "-vcodec", "png", "-pix_fmt", "rgba", &pattern], TimeStamp::zero(), end)?;
+
+        let fps = self.get_fps();
+        Ok((0..end.as_num_frames(fps)).map(|index| {
+            let path = PathBuf::from(format!("{}/{}_{:04}.png", end_dir, prefix, index + PNG_SEQUENCE_START_NUMBER));
+            (TimeStamp::from_frames(index, fps), path)
+        }).collect())
+    }
+}
+
+/// Output arguments for an H.264 video with no audio track.
+fn video_output_args(output: &str) -> [&str; 4] {
+    ["-an", "-vcodec", "libx264", output]
+}
+
+/// Pipes the frames in `[start, end)` through ffmpeg, writing wherever `output_args` say.
+pub(crate) fn encode_range<C: Canvas + ?Sized>(canvas: &C, end_dir: &str, output_args: &[&str], start: TimeStamp, end: TimeStamp) -> Result<(), FerrociousError> {
+    encode_with(canvas, end_dir, output_args, start, end, |write_frame| canvas.render_timeline(&start, &end, write_frame))
+}
+
+/// Like `encode_range`, but `render` decides how the frames in `[start, end)` are produced,
+/// handing each one to the writer it's given in order.
+pub(crate) fn encode_with<C: Canvas + ?Sized>(
+    canvas: &C,
+    end_dir: &str,
+    output_args: &[&str],
+    start: TimeStamp,
+    end: TimeStamp,
+    render: impl FnOnce(&mut dyn FnMut(ndarray::Array2<u32>) -> Result<(), SendError<Vec<u8>>>) -> Result<(), SendError<Vec<u8>>>,
+) -> Result<(), FerrociousError> {
+    // compare frame counts rather than fields, since a timestamp's frame can exceed the fps
+    let fps = canvas.get_fps();
+    if end.as_num_frames(fps) <= start.as_num_frames(fps) {
+        return Err(FerrociousError::EmptyTimeline);
+    }
+
+    if !Path::new(end_dir).exists() {
+        fs::create_dir_all(end_dir)?;
     }
 
+    let (width, height): (u32, u32) = canvas.get_width_and_height();
+
+    let mut process = C::launch_writing_subprocess(width, height, fps, output_args)?;
+    let stdin = process.stdin.take().expect("we should have stdin");
+    let (frame_sender, writer) = spawn_frame_writer(stdin);
+
+    // a failed send means the writer stopped on an error, which is reported when it's joined below
+    let _ = render(&mut |frame| frame_sender.send(C::frame_to_bytes(&frame)));
+
+    // dropping the sender lets the writer drain and return stdin, which is then closed
+    drop(frame_sender);
+    // the writer hands stdin back; drop it so ffmpeg sees the end of its input before we wait on it
+    let written = writer.join().expect("the frame writer shouldn't panic").map(drop);
+
+    // ffmpeg bailing out early shows up as a broken pipe, so its exit status is the real cause
+    let status = process.wait()?;
+    if !status.success() {
+        return Err(FerrociousError::EncoderExit(status));
+    }
+    written?;
+    Ok(())
 }
@@ -1,9 +1,34 @@
 use crate::mutator::timestamp::TimeStamp;
 
 pub trait Entity {
+    /// Draws the entity's pixels for `active_frame` as packed RGBA. Needs nothing beyond the
+    /// timestamp and fps, so entities can be unit-tested without a canvas or ffmpeg.
     fn render(&self, active_frame: &TimeStamp, fps: u32)-> ndarray::Array2<u32>;
     fn get_size(&self) -> (u32, u32);
     fn is_active_at(&self, frame: &TimeStamp) -> bool;
     fn upper_left_coords(&self) -> (u32, u32);
     fn tick(&mut self, frame: &TimeStamp);
+
+    /// Whether this entity is alpha-blended over what's beneath it rather than painted opaquely.
+    fn get_blending(&self) -> bool {
+        false
+    }
+
+    /// Alpha threshold in [0, 1] for hard cutouts: pixels below it aren't drawn at all and the
+    /// rest are drawn fully opaque. Takes precedence over `get_blending`.
+    fn alpha_cutoff(&self) -> Option<f32> {
+        None
+    }
+
+    /// Draw priority: entities are drawn in ascending z-index, so higher values end up on top.
+    /// Ties keep the order the canvas returned them in.
+    fn z_index(&self) -> i32 {
+        0
+    }
+
+    /// Whether `render` depends only on the timestamp and fps. Return false if it relies on state
+    /// shared across frames; a single impure entity makes parallel renders fall back to serial.
+    fn is_pure(&self) -> bool {
+        true
+    }
 }
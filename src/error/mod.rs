@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use subprocess::{ExitStatus, PopenError};
+
+#[derive(Debug)]
+pub enum FerrociousError {
+    /// A save was asked to end at or before its start, which would leave ffmpeg with no frames.
+    EmptyTimeline,
+    /// The output directory couldn't be created, or frames couldn't be piped to ffmpeg.
+    Io(io::Error),
+    /// ffmpeg couldn't be launched or waited on, usually because it isn't installed.
+    Encode(PopenError),
+    /// ffmpeg ran but exited unsuccessfully.
+    EncoderExit(ExitStatus),
+}
+
+impl fmt::Display for FerrociousError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FerrociousError::EmptyTimeline => write!(f, "the timeline is empty, so there are no frames to save"),
+            FerrociousError::Io(err) => write!(f, "io error while saving: {}", err),
+            FerrociousError::Encode(err) => write!(f, "couldn't run ffmpeg: {}", err),
+            FerrociousError::EncoderExit(status) => write!(f, "ffmpeg exited unsuccessfully: {:?}", status),
+        }
+    }
+}
+
+impl Error for FerrociousError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FerrociousError::Io(err) => Some(err),
+            FerrociousError::Encode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FerrociousError {
+    fn from(err: io::Error) -> Self {
+        FerrociousError::Io(err)
+    }
+}
+
+impl From<PopenError> for FerrociousError {
+    fn from(err: PopenError) -> Self {
+        FerrociousError::Encode(err)
+    }
+}
@@ -3,12 +3,11 @@
 //! `my_crate` is a collection of utilities to make performing certain
 //! calculations more convenient.
 
-/// Adds one to the number given.
-// --snip-
-
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 pub mod tests;
 pub mod utils;
 pub mod canvas;
 pub mod entity;
+pub mod error;
 pub mod mutator;
@@ -2,10 +2,3 @@ fn main() {
 
 
 }
-
-fn parse_config() {
-    
-}
-
-
-
@@ -3,8 +3,9 @@ use std::cmp::Ordering;
 use std::cmp::Ordering::{Equal, Greater, Less};
 use std::fmt;
 use std::fmt::Formatter;
+use std::time::Duration;
 
-#[derive(Eq, Debug)]
+#[derive(Eq, Debug, Clone, Copy)]
 pub struct TimeStamp {
     pub minute: u8,
     pub second: u8,
@@ -20,16 +21,58 @@ impl TimeStamp {
         }
     }
 
+    pub fn zero() -> Self {
+        TimeStamp::new(None, None, None)
+    }
+
+    /// The timestamp `frames` frames after 0:0:0, saturating at 255:59:(fps - 1).
+    /// Panics unless `fps` is between 1 and 255, since the frame field is a `u8`.
+    pub fn from_frames(frames: u32, fps: u32) -> Self {
+        assert!((1..=u8::MAX as u32).contains(&fps), "fps must be between 1 and 255, got {}", fps);
+
+        let frames_per_minute = fps * 60;
+        if frames / frames_per_minute > u8::MAX as u32 {
+            return TimeStamp { minute: u8::MAX, second: 59, frame: (fps - 1) as u8 };
+        }
+
+        TimeStamp {
+            minute: (frames / frames_per_minute) as u8,
+            second: (frames % frames_per_minute / fps) as u8,
+            frame: (frames % fps) as u8,
+        }
+    }
+
+    /// The timestamp `secs` seconds after 0:0:0, rounded to the nearest frame. Negative values give 0:0:0.
+    /// Has the same saturation and `fps` limits as `from_frames`.
+    pub fn from_seconds(secs: f32, fps: u32) -> Self {
+        TimeStamp::from_frames((secs * fps as f32).round().max(0.0) as u32, fps)
+    }
+
+    /// Panics if `fps` is 0.
+    pub fn to_duration(&self, fps: u32) -> Duration {
+        assert!(fps > 0, "fps must be positive");
+        Duration::from_secs_f64(self.as_num_frames(fps) as f64 / fps as f64)
+    }
+
+    /// Adds two timestamps, carrying frames into seconds and seconds into minutes at `fps`.
+    pub fn add_at_fps(&self, other: &TimeStamp, fps: u32) -> Self {
+        TimeStamp::from_frames(self.as_num_frames(fps) + other.as_num_frames(fps), fps)
+    }
+
+    /// Subtracts `other` from this timestamp at `fps`, saturating at 0:0:0.
+    pub fn sub_at_fps(&self, other: &TimeStamp, fps: u32) -> Self {
+        TimeStamp::from_frames(self.as_num_frames(fps).saturating_sub(other.as_num_frames(fps)), fps)
+    }
 
     pub fn increment(&mut self) {
         self.frame += 1;
 
-        if self.frame > DEFAULT_FPS { //TODO: allow for context-based FPS
+        if self.frame >= DEFAULT_FPS { //TODO: allow for context-based FPS
             self.frame = 0;
             self.second += 1;
         }
 
-        if self.second > 59 {
+        if self.second >= 60 {
             self.second = 0;
             self.minute += 1;
         }
@@ -38,6 +81,25 @@ impl TimeStamp {
     pub fn time_as_array(&self) -> [u8;3] {
         [self.minute, self.second, self.frame]
     }
+
+    /// Total number of frames from 0:0:0 up to this timestamp at the given fps.
+    pub fn as_num_frames(&self, fps: u32) -> u32 {
+        (self.minute as u32 * 60 + self.second as u32) * fps + self.frame as u32
+    }
+
+    /// How far this timestamp is through `range`, as a fraction clamped to [0, 1].
+    /// Handy inside `Entity::render` for animating relative to an entity's own active range.
+    pub fn progress_through(&self, range: &[TimeStamp; 2], fps: u32) -> f32 {
+        let start = range[0].as_num_frames(fps) as f32;
+        let end = range[1].as_num_frames(fps) as f32;
+        let current = self.as_num_frames(fps) as f32;
+
+        if end <= start {
+            return if current >= end { 1.0 } else { 0.0 };
+        }
+
+        ((current - start) / (end - start)).clamp(0.0, 1.0)
+    }
 }
 
 impl PartialEq<Self> for TimeStamp {
@@ -50,15 +112,13 @@ impl PartialEq<Self> for TimeStamp {
 
 impl PartialOrd for TimeStamp {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        return {
-            if self < other {
-                Some(Less)
-            } else if self == other {
-                Some(Equal)
-            } else {
-                Some(Greater)
-            }
-        };
+        if self < other {
+            Some(Less)
+        } else if self == other {
+            Some(Equal)
+        } else {
+            Some(Greater)
+        }
     }
 
     fn lt(&self, other: &Self) -> bool {
@@ -69,10 +129,6 @@ impl PartialOrd for TimeStamp {
                         other.frame > self.frame)))
     }
 
-    fn le(&self, other: &Self) -> bool {
-        self < other || self == other
-    }
-
     fn gt(&self, other: &Self) -> bool {
         other.minute < self.minute ||
             (other.minute == self.minute &&
@@ -80,10 +136,6 @@ impl PartialOrd for TimeStamp {
                     (other.second == self.second &&
                         other.frame < self.frame)))
     }
-
-    fn ge(&self, other: &Self) -> bool {
-        self > other || self == other
-    }
 }
 
 impl fmt::Display for TimeStamp {
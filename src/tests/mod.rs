@@ -1,61 +1,693 @@
 mod tests {
+    use std::convert::Infallible;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::thread::ThreadId;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use ndarray::Array2;
+    #[cfg(unix)]
+    use subprocess::{ExitStatus, Popen, PopenConfig, PopenError, Redirection};
+    use crate::canvas::{blend_over, ffmpeg_command, spawn_frame_writer, Canvas};
+    use crate::entity::Entity;
+    use crate::error::FerrociousError;
     use crate::mutator::timestamp::TimeStamp;
     use crate::utils::defaults::DEFAULT_FPS;
 
+    #[derive(Clone)]
+    struct PlainEntity {
+        color: u32,
+        size: (u32, u32),
+        upper_left: (u32, u32),
+        blending: bool,
+        cutoff: Option<f32>,
+        z_index: i32,
+    }
+
+    impl PlainEntity {
+        fn new(color: u32, size: (u32, u32), upper_left: (u32, u32)) -> Self {
+            PlainEntity { color, size, upper_left, blending: false, cutoff: None, z_index: 0 }
+        }
+    }
+
+    impl Entity for PlainEntity {
+        fn render(&self, _active_frame: &TimeStamp, _fps: u32) -> Array2<u32> {
+            Array2::from_elem((self.size.0 as usize, self.size.1 as usize), self.color)
+        }
+
+        fn get_size(&self) -> (u32, u32) {
+            self.size
+        }
+
+        fn is_active_at(&self, _frame: &TimeStamp) -> bool {
+            true
+        }
+
+        fn upper_left_coords(&self) -> (u32, u32) {
+            self.upper_left
+        }
+
+        fn tick(&mut self, _frame: &TimeStamp) {}
+
+        fn get_blending(&self) -> bool {
+            self.blending
+        }
+
+        fn alpha_cutoff(&self) -> Option<f32> {
+            self.cutoff
+        }
+
+        fn z_index(&self) -> i32 {
+            self.z_index
+        }
+    }
+
+    /// Encodes its frame number in its pixel, and takes longer on earlier frames so parallel
+    /// renders tend to finish out of order.
+    struct ClockEntity;
+
+    impl Entity for ClockEntity {
+        fn render(&self, active_frame: &TimeStamp, fps: u32) -> Array2<u32> {
+            let frame_number = active_frame.as_num_frames(fps);
+            thread::sleep(Duration::from_millis(8 - (frame_number % 8) as u64));
+            Array2::from_elem((1, 1), frame_number << 16 | 0xFF)
+        }
+
+        fn get_size(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn is_active_at(&self, _frame: &TimeStamp) -> bool {
+            true
+        }
+
+        fn upper_left_coords(&self) -> (u32, u32) {
+            (0, 0)
+        }
+
+        fn tick(&mut self, _frame: &TimeStamp) {}
+    }
+
+    struct ClockCanvas;
+
+    impl Canvas for ClockCanvas {
+        fn construct(&self) {}
+
+        fn get_width_and_height(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn get_fps(&self) -> u32 {
+            DEFAULT_FPS as u32
+        }
+
+        fn get_entities(&self) -> Vec<impl Entity> {
+            vec![ClockEntity]
+        }
+
+        fn get_background(&self) -> Array2<u32> {
+            Array2::from_elem((1, 1), 0x000000FF)
+        }
+    }
+
+    /// Records which thread rendered it, so tests can tell parallel renders from serial ones.
+    #[derive(Clone)]
+    struct ThreadEntity {
+        pure: bool,
+        threads: Arc<Mutex<Vec<ThreadId>>>,
+    }
+
+    impl Entity for ThreadEntity {
+        fn render(&self, _active_frame: &TimeStamp, _fps: u32) -> Array2<u32> {
+            self.threads.lock().unwrap().push(thread::current().id());
+            Array2::from_elem((1, 1), 0xFFFFFFFF)
+        }
+
+        fn get_size(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn is_active_at(&self, _frame: &TimeStamp) -> bool {
+            true
+        }
+
+        fn upper_left_coords(&self) -> (u32, u32) {
+            (0, 0)
+        }
+
+        fn tick(&mut self, _frame: &TimeStamp) {}
+
+        fn is_pure(&self) -> bool {
+            self.pure
+        }
+    }
+
+    struct ThreadCanvas {
+        entity: ThreadEntity,
+    }
+
+    impl ThreadCanvas {
+        fn new(pure: bool) -> Self {
+            ThreadCanvas { entity: ThreadEntity { pure, threads: Arc::new(Mutex::new(vec![])) } }
+        }
+
+        fn render_threads(&self) -> Vec<ThreadId> {
+            let end = TimeStamp::new(None, None, Some(8));
+            self.render_timeline_parallel(&TimeStamp::zero(), &end, 4, |_frame| Ok::<(), Infallible>(())).unwrap();
+            self.entity.threads.lock().unwrap().clone()
+        }
+    }
+
+    impl Canvas for ThreadCanvas {
+        fn construct(&self) {}
+
+        fn get_width_and_height(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn get_fps(&self) -> u32 {
+            DEFAULT_FPS as u32
+        }
+
+        fn get_entities(&self) -> Vec<impl Entity> {
+            vec![self.entity.clone()]
+        }
+
+        fn get_background(&self) -> Array2<u32> {
+            Array2::from_elem((1, 1), 0x000000FF)
+        }
+    }
+
+    /// Stands in for ffmpeg: with an exit code of 0 the "encoder" swallows every frame and
+    /// succeeds, otherwise it exits with that code straight away, as if it rejected its arguments.
+    #[cfg(unix)]
+    struct StubEncoderCanvas<const EXIT_CODE: u8>;
+
+    #[cfg(unix)]
+    impl<const EXIT_CODE: u8> Canvas for StubEncoderCanvas<EXIT_CODE> {
+        fn construct(&self) {}
+
+        fn get_width_and_height(&self) -> (u32, u32) {
+            (256, 256)
+        }
+
+        fn get_fps(&self) -> u32 {
+            DEFAULT_FPS as u32
+        }
+
+        fn get_entities(&self) -> Vec<impl Entity> {
+            Vec::<PlainEntity>::new()
+        }
+
+        fn get_background(&self) -> Array2<u32> {
+            Array2::from_elem((256, 256), 0x000000FF)
+        }
+
+        fn launch_writing_subprocess(_width: u32, _height: u32, _fps: u32, _output_args: &[&str]) -> Result<Popen, PopenError> {
+            let script = if EXIT_CODE == 0 { "cat > /dev/null".to_string() } else { format!("exit {}", EXIT_CODE) };
+            Popen::create(&["sh", "-c", &script], PopenConfig {
+                stdin: Redirection::Pipe,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// A sink that takes `delay` per write, standing in for ffmpeg.
+    struct SlowSink {
+        delay: Duration,
+    }
+
+    impl Write for SlowSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct TestCanvas {
+        size: (u32, u32),
+        background: u32,
+        entities: Vec<PlainEntity>,
+        progress: Mutex<Vec<(u32, u32, u32)>>,
+    }
+
+    impl TestCanvas {
+        fn new(size: (u32, u32), background: u32, entities: Vec<PlainEntity>) -> Self {
+            TestCanvas { size, background, entities, progress: Mutex::new(vec![]) }
+        }
+    }
+
+    impl Canvas for TestCanvas {
+        fn construct(&self) {}
+
+        fn get_width_and_height(&self) -> (u32, u32) {
+            self.size
+        }
+
+        fn get_fps(&self) -> u32 {
+            DEFAULT_FPS as u32
+        }
+
+        fn get_entities(&self) -> Vec<impl Entity> {
+            self.entities.clone()
+        }
+
+        fn get_background(&self) -> Array2<u32> {
+            Array2::from_elem((self.size.0 as usize, self.size.1 as usize), self.background)
+        }
+
+        fn on_frame_rendered(&self, current: &TimeStamp, index: u32, total_frames: u32) {
+            self.progress.lock().unwrap().push((current.as_num_frames(self.get_fps()), index, total_frames));
+        }
+    }
+
     // timestamp tests
     #[test]
     fn test_timestamp_incrementer() {
-        let mut ts = TimeStamp::new(1, 3, 2);
-        for _n in 0..DEFAULT_FPS + 1{
+        let mut ts = TimeStamp::new(Some(1), Some(3), Some(2));
+        for _n in 0..DEFAULT_FPS {
             ts.increment();
         }
-        assert_eq!(ts, TimeStamp::new(1, 4, 2));
+        assert_eq!(ts, TimeStamp::new(Some(1), Some(4), Some(2)));
     }
 
     #[test]
     fn test_timestamp_minute_rollover() {
-        let mut ts = TimeStamp::new(1, 59, DEFAULT_FPS);
+        let mut ts = TimeStamp::new(Some(1), Some(59), Some(DEFAULT_FPS - 1));
         ts.increment();
-        assert_eq!(ts, TimeStamp::new(2, 0, 0));
+        assert_eq!(ts, TimeStamp::new(Some(2), Some(0), Some(0)));
     }
 
     #[test]
     fn test_timestamp_lt() {
-        let ts_less = TimeStamp::new(1, 3, 2);
-        let ts_more = TimeStamp::new(3, 0, 4);
+        let ts_less = TimeStamp::new(Some(1), Some(3), Some(2));
+        let ts_more = TimeStamp::new(Some(3), Some(0), Some(4));
         assert!(ts_less < ts_more);
     }
 
     #[test]
     fn test_timestamp_gt() {
-        let ts_less = TimeStamp::new(1, 3, 2);
-        let ts_more = TimeStamp::new(3, 0, 4);
+        let ts_less = TimeStamp::new(Some(1), Some(3), Some(2));
+        let ts_more = TimeStamp::new(Some(3), Some(0), Some(4));
         assert!(ts_more > ts_less);
     }
 
     #[test]
     fn test_timestamp_eq() {
-        let ts_one = TimeStamp::new(1, 0, 0);
-        let ts_two = TimeStamp::new(1, 0, 0);
+        let ts_one = TimeStamp::new(Some(1), Some(0), Some(0));
+        let ts_two = TimeStamp::new(Some(1), Some(0), Some(0));
         assert_eq!(ts_one, ts_two);
     }
 
     #[test]
     fn test_timestamp_leq() {
-        let ts_less = TimeStamp::new(1, 3, 2);
-        let ts_more = TimeStamp::new(3, 0, 4);
+        let ts_less = TimeStamp::new(Some(1), Some(3), Some(2));
+        let ts_more = TimeStamp::new(Some(3), Some(0), Some(4));
         assert!(ts_less <= ts_more);
     }
 
     #[test]
     fn test_timestamp_geq() {
-        let ts_less = TimeStamp::new(1, 3, 2);
-        let ts_more = TimeStamp::new(3, 0, 4);
+        let ts_less = TimeStamp::new(Some(1), Some(3), Some(2));
+        let ts_more = TimeStamp::new(Some(3), Some(0), Some(4));
         assert!(ts_more >= ts_less);
     }
 
     #[test]
     fn test_timestamp_array() {
-        assert_eq!(TimeStamp::new(1, 3, 2).time_as_array(), [1, 3, 2]);
+        assert_eq!(TimeStamp::new(Some(1), Some(3), Some(2)).time_as_array(), [1, 3, 2]);
+    }
+
+    #[test]
+    fn test_timestamp_num_frames() {
+        let ts = TimeStamp::new(Some(1), Some(3), Some(2));
+        assert_eq!(ts.as_num_frames(24), (60 + 3) * 24 + 2);
+    }
+
+    #[test]
+    fn test_timestamp_progress_midpoint() {
+        let range = [TimeStamp::new(None, Some(1), None), TimeStamp::new(None, Some(3), None)];
+        let ts = TimeStamp::new(None, Some(2), None);
+        assert!((ts.progress_through(&range, 24) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_timestamp_progress_clamped() {
+        let range = [TimeStamp::new(None, Some(1), None), TimeStamp::new(None, Some(3), None)];
+        assert_eq!(TimeStamp::new(None, None, Some(5)).progress_through(&range, 24), 0.0);
+        assert_eq!(TimeStamp::new(Some(1), None, None).progress_through(&range, 24), 1.0);
+    }
+
+    #[test]
+    fn test_timestamp_progress_degenerate_unnormalized() {
+        // at 24 fps this range runs from frame 30 back to frame 24; 0:0:40 is past both ends even though it sorts before 0:1:0 by field
+        let range = [TimeStamp::new(None, None, Some(30)), TimeStamp::new(None, Some(1), None)];
+        assert_eq!(TimeStamp::new(None, None, Some(40)).progress_through(&range, 24), 1.0);
+        assert_eq!(TimeStamp::new(None, None, Some(10)).progress_through(&range, 24), 0.0);
+    }
+
+    #[test]
+    fn test_timestamp_add_across_minute() {
+        let ts = TimeStamp::new(Some(1), Some(59), Some(20)).add_at_fps(&TimeStamp::new(None, Some(1), Some(6)), 24);
+        assert_eq!(ts, TimeStamp::new(Some(2), Some(1), Some(2)));
+    }
+
+    #[test]
+    fn test_timestamp_add_exact_minute() {
+        let ts = TimeStamp::new(None, Some(30), None).add_at_fps(&TimeStamp::new(None, Some(30), None), 24);
+        assert_eq!(ts, TimeStamp::new(Some(1), Some(0), Some(0)));
+    }
+
+    #[test]
+    fn test_timestamp_sub_saturates() {
+        let ts = TimeStamp::new(None, Some(2), None).sub_at_fps(&TimeStamp::new(None, Some(3), Some(1)), 24);
+        assert_eq!(ts, TimeStamp::zero());
+    }
+
+    #[test]
+    fn test_timestamp_sub_borrows() {
+        let ts = TimeStamp::new(Some(1), None, Some(2)).sub_at_fps(&TimeStamp::new(None, None, Some(6)), 30);
+        assert_eq!(ts, TimeStamp::new(None, Some(59), Some(26)));
+    }
+
+    #[test]
+    fn test_timestamp_seconds_and_frames() {
+        assert_eq!(TimeStamp::from_seconds(62.5, 24), TimeStamp::new(Some(1), Some(2), Some(12)));
+        assert_eq!(TimeStamp::from_frames(24 * 61 + 3, 24), TimeStamp::new(Some(1), Some(1), Some(3)));
+        assert_eq!(TimeStamp::from_seconds(-1.0, 24), TimeStamp::zero());
+        assert_eq!(TimeStamp::new(None, Some(2), Some(12)).to_duration(24), std::time::Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn test_timestamp_from_frames_saturates() {
+        let past_max = (256 * 60 + 5) * 24;
+        assert_eq!(TimeStamp::from_frames(past_max, 24), TimeStamp::new(Some(255), Some(59), Some(23)));
+        assert_eq!(TimeStamp::from_frames(u32::MAX, 30), TimeStamp::new(Some(255), Some(59), Some(29)));
+    }
+
+    #[test]
+    #[should_panic(expected = "fps must be between 1 and 255")]
+    fn test_timestamp_from_frames_rejects_zero_fps() {
+        TimeStamp::from_frames(10, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "fps must be between 1 and 255")]
+    fn test_timestamp_from_frames_rejects_oversized_fps() {
+        TimeStamp::from_frames(10, 256);
+    }
+
+    // canvas tests
+    #[test]
+    fn test_frame_writer_preserves_order() {
+        let (sender, writer) = spawn_frame_writer(Vec::new());
+        for n in 0..20u8 {
+            sender.send(vec![n; 4]).unwrap();
+        }
+        drop(sender);
+        let written = writer.join().unwrap().unwrap();
+        let expected: Vec<u8> = (0..20u8).flat_map(|n| [n; 4]).collect();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn test_frame_writer_overlaps_rendering() {
+        const FRAMES: usize = 10;
+        let delay = Duration::from_millis(20);
+        let render = || {
+            thread::sleep(delay);
+            vec![0u8; 16]
+        };
+
+        let inline_start = Instant::now();
+        let mut sink = SlowSink { delay };
+        for _ in 0..FRAMES {
+            sink.write_all(&render()).unwrap();
+        }
+        let inline = inline_start.elapsed();
+
+        let threaded_start = Instant::now();
+        let (sender, writer) = spawn_frame_writer(SlowSink { delay });
+        for _ in 0..FRAMES {
+            sender.send(render()).unwrap();
+        }
+        drop(sender);
+        writer.join().unwrap().unwrap();
+        let threaded = threaded_start.elapsed();
+
+        assert!(threaded < inline * 3 / 4, "writer thread took {:?}, inline took {:?}", threaded, inline);
+    }
+
+    #[test]
+    fn test_unmask_keeps_every_channel() {
+        assert_eq!(TestCanvas::unmask(0x11223344), [0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test]
+    fn test_blend_over_half_alpha() {
+        assert_eq!(blend_over(0xFF000080, 0x0000FFFF), 0x80007FFF);
+        assert_eq!(blend_over(0xFF0000FF, 0x0000FFFF), 0xFF0000FF);
+        assert_eq!(blend_over(0xFF000000, 0x0000FFFF), 0x0000FFFF);
+    }
+
+    #[test]
+    fn test_frame_bytes_are_row_major() {
+        // a 3x2 canvas with a 1x2 column at x = 1
+        let canvas = TestCanvas::new((3, 2), 0x000000FF, vec![PlainEntity::new(0xFF0000FF, (1, 2), (1, 0))]);
+        let bytes = TestCanvas::frame_to_bytes(&canvas.render_frame(&TimeStamp::zero()));
+
+        let black = [0x00, 0x00, 0x00, 0xFF];
+        let red = [0xFF, 0x00, 0x00, 0xFF];
+        let row = [black, red, black].concat();
+        assert_eq!(bytes, [row.clone(), row].concat());
+    }
+
+    #[test]
+    fn test_overlapping_translucent_entities_blend() {
+        let quad = |color, upper_left| PlainEntity { blending: true, ..PlainEntity::new(color, (2, 2), upper_left) };
+        let canvas = TestCanvas::new((3, 3), 0x000000FF, vec![quad(0xFF000080, (0, 0)), quad(0x0000FF80, (1, 1))]);
+        let frame = canvas.render_frame(&TimeStamp::new(None, None, None));
+
+        assert_eq!(frame[[0, 0]], blend_over(0xFF000080, 0x000000FF));
+        assert_eq!(frame[[2, 2]], blend_over(0x0000FF80, 0x000000FF));
+        let overlap = TestCanvas::unmask(frame[[1, 1]]);
+        assert!(overlap[0] > 0 && overlap[2] > 0, "overlap should mix red and blue, got {:?}", overlap);
+        assert_ne!(frame[[1, 1]], frame[[2, 2]]);
+    }
+
+    #[test]
+    fn test_alpha_cutoff_discards_below_threshold() {
+        let cutout = |color, upper_left| PlainEntity { blending: true, cutoff: Some(0.5), ..PlainEntity::new(color, (1, 2), upper_left) };
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![cutout(0xFF000040, (0, 0)), cutout(0x00FF00C0, (1, 0))]);
+        let frame = canvas.render_frame(&TimeStamp::zero());
+
+        assert_eq!(frame[[0, 0]], 0x000000FF);
+        assert_eq!(frame[[0, 1]], 0x000000FF);
+        assert_eq!(frame[[1, 0]], 0x00FF00FF);
+        assert_eq!(frame[[1, 1]], 0x00FF00FF);
+    }
+
+    #[test]
+    fn test_entities_drawn_in_z_order() {
+        let layer = |color, x, z_index| PlainEntity { z_index, ..PlainEntity::new(color, (3 - x, 1), (x, 0)) };
+        let canvas = TestCanvas::new((3, 1), 0x000000FF, vec![
+            layer(0x0000FFFF, 2, 2),
+            layer(0xFF0000FF, 0, 0),
+            layer(0x00FF00FF, 1, 1),
+        ]);
+        let frame = canvas.render_frame(&TimeStamp::zero());
+
+        assert_eq!(frame.iter().copied().collect::<Vec<u32>>(), vec![0xFF0000FF, 0x00FF00FF, 0x0000FFFF]);
+    }
+
+    #[test]
+    fn test_equal_z_keeps_entity_order() {
+        let (background, first, second, third) = (0x000000FF, 0xFF000080, 0x00FF0080, 0x0000FF80);
+        let translucent = |color| PlainEntity { blending: true, ..PlainEntity::new(color, (1, 1), (0, 0)) };
+        let canvas = TestCanvas::new((1, 1), background, vec![translucent(first), translucent(second), translucent(third)]);
+
+        let frame = canvas.render_frame(&TimeStamp::zero());
+        assert_eq!(frame[[0, 0]], blend_over(third, blend_over(second, blend_over(first, background))));
+        assert_eq!(canvas.render_frame(&TimeStamp::zero()), frame);
+    }
+
+    #[test]
+    fn test_save_empty_timeline_errors() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        let result = canvas.save("target/never_written", "empty.mp4", TimeStamp::zero());
+        assert!(matches!(result, Err(FerrociousError::EmptyTimeline)));
+        assert!(!std::path::Path::new("target/never_written").exists());
+    }
+
+    #[test]
+    fn test_save_unwritable_dir_errors() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        // a directory can't be created underneath a regular file
+        let result = canvas.save("Cargo.toml/out", "clip.mp4", TimeStamp::new(None, Some(1), None));
+        assert!(matches!(result, Err(FerrociousError::Io(_))));
+    }
+
+    #[test]
+    fn test_progress_reported_per_frame() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        let end = TimeStamp::new(None, None, Some(10));
+        let mut frames_written = 0;
+        canvas.render_timeline(&TimeStamp::zero(), &end, |_frame| {
+            frames_written += 1;
+            Ok::<(), Infallible>(())
+        }).unwrap();
+
+        let progress = canvas.progress.lock().unwrap();
+        assert_eq!(progress.len(), 10);
+        assert_eq!(frames_written, 10);
+        assert!(progress.iter().all(|&(_, _, total)| total == 10));
+        assert!(progress.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(progress.iter().map(|&(_, index, _)| index).collect::<Vec<u32>>(), (0..10).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_render_timeline_sub_range() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        let start = TimeStamp::new(None, None, Some(12));
+        let end = TimeStamp::new(None, Some(1), None);
+        let mut frames_written = 0;
+        canvas.render_timeline(&start, &end, |_frame| {
+            frames_written += 1;
+            Ok::<(), Infallible>(())
+        }).unwrap();
+
+        assert_eq!(frames_written, 12);
+        let progress = canvas.progress.lock().unwrap();
+        // timestamps stay absolute, but the index counts from the start of the range
+        assert_eq!(progress.first(), Some(&(12, 0, 12)));
+        assert_eq!(progress.last(), Some(&(23, 11, 12)));
+    }
+
+    #[test]
+    fn test_save_range_backwards_errors() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        let result = canvas.save_range("target/never_written", "range.mp4", TimeStamp::new(None, Some(2), None), TimeStamp::new(None, Some(1), None));
+        assert!(matches!(result, Err(FerrociousError::EmptyTimeline)));
+    }
+
+    #[test]
+    fn test_ffmpeg_command_appends_output_args() {
+        let command = ffmpeg_command(4, 3, 24, &["-vcodec", "png", "out/frame_%04d.png"]);
+        assert!(command.windows(2).any(|pair| pair == ["-s", "4x3"]));
+        assert!(command.windows(2).any(|pair| pair == ["-i", "-"]));
+        assert_eq!(command[command.len() - 3..], ["-vcodec", "png", "out/frame_%04d.png"]);
+    }
+
+    #[test]
+    fn test_save_png_sequence_empty_timeline_errors() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        let result = canvas.save_png_sequence("target/never_written", "frame", TimeStamp::zero());
+        assert!(matches!(result, Err(FerrociousError::EmptyTimeline)));
+    }
+
+    #[test]
+    fn test_save_with_missing_audio_errors() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        let audio = std::path::Path::new("target/no_such_track.wav");
+        let result = canvas.save_with_audio("target/never_written", "clip.mp4", TimeStamp::new(None, Some(1), None), audio);
+        assert!(matches!(result, Err(FerrociousError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_save_range_unnormalized_empty_errors() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        // frame 30 sorts before 0:1:0 by field, but is six frames after it at 24 fps
+        let start = TimeStamp::new(None, None, Some(30));
+        let end = TimeStamp::new(None, Some(1), None);
+        let result = canvas.save_range("target/never_written", "range.mp4", start, end);
+        assert!(matches!(result, Err(FerrociousError::EmptyTimeline)));
+    }
+
+    #[test]
+    fn test_parallel_render_keeps_frame_order() {
+        let canvas = ClockCanvas;
+        let start = TimeStamp::new(None, None, Some(3));
+        let end = TimeStamp::new(None, Some(1), Some(5));
+
+        let mut serial = vec![];
+        canvas.render_timeline(&start, &end, |frame| {
+            serial.push(frame[[0, 0]]);
+            Ok::<(), Infallible>(())
+        }).unwrap();
+
+        let mut parallel = vec![];
+        canvas.render_timeline_parallel(&start, &end, 4, |frame| {
+            parallel.push(frame[[0, 0]]);
+            Ok::<(), Infallible>(())
+        }).unwrap();
+
+        let expected: Vec<u32> = (3..29).map(|frame_number| frame_number << 16 | 0xFF).collect();
+        assert_eq!(serial, expected);
+        assert_eq!(parallel, expected);
+    }
+
+    #[test]
+    fn test_parallel_render_reports_progress_in_order() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![PlainEntity::new(0xFF0000FF, (1, 1), (0, 0))]);
+        let end = TimeStamp::new(None, None, Some(10));
+        canvas.render_timeline_parallel(&TimeStamp::zero(), &end, 3, |_frame| Ok::<(), Infallible>(())).unwrap();
+
+        let progress = canvas.progress.lock().unwrap();
+        assert_eq!(progress.iter().map(|&(frame, _, _)| frame).collect::<Vec<u32>>(), (0..10).collect::<Vec<u32>>());
+        assert_eq!(progress.iter().map(|&(_, index, _)| index).collect::<Vec<u32>>(), (0..10).collect::<Vec<u32>>());
+        assert!(progress.iter().all(|&(_, _, total)| total == 10));
+    }
+
+    #[test]
+    fn test_save_parallel_empty_timeline_errors() {
+        let canvas = TestCanvas::new((2, 2), 0x000000FF, vec![]);
+        let result = canvas.save_parallel("target/never_written", "clip.mp4", TimeStamp::zero(), 4);
+        assert!(matches!(result, Err(FerrociousError::EmptyTimeline)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_encoder_exit_reported_over_broken_pipe() {
+        // each frame is bigger than a pipe buffer, so writing fails once the encoder has exited
+        let result = StubEncoderCanvas::<3>.save("target/failing_encoder", "clip.mp4", TimeStamp::new(None, Some(1), None));
+        assert!(matches!(result, Err(FerrociousError::EncoderExit(ExitStatus::Exited(3)))), "got {:?}", result);
+    }
+
+    #[test]
+    fn test_impure_entity_forces_serial_render() {
+        let threads = ThreadCanvas::new(false).render_threads();
+        assert_eq!(threads.len(), 8);
+        assert!(threads.iter().all(|&id| id == thread::current().id()));
+    }
+
+    #[test]
+    fn test_pure_entity_renders_in_parallel() {
+        let threads = ThreadCanvas::new(true).render_threads();
+        assert_eq!(threads.len(), 8);
+        assert!(threads.iter().all(|&id| id != thread::current().id()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_save_closes_encoder_input_before_waiting() {
+        // the stub only exits once its stdin is closed, so this hangs if save holds on to the pipe
+        let result = StubEncoderCanvas::<0>.save("target/stub_encoder", "clip.mp4", TimeStamp::new(None, None, Some(3)));
+        assert!(result.is_ok(), "got {:?}", result);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_png_sequence_maps_frames_to_files() {
+        let end = TimeStamp::new(None, None, Some(3));
+        let frames = StubEncoderCanvas::<0>.save_png_sequence("target/png_sequence", "frame", end).unwrap();
+
+        assert_eq!(frames.len(), 3);
+        assert!(frames.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(frames[0], (TimeStamp::zero(), std::path::PathBuf::from("target/png_sequence/frame_0001.png")));
+        assert_eq!(frames[2].1, std::path::PathBuf::from("target/png_sequence/frame_0003.png"));
     }
-}
\ No newline at end of file
+}
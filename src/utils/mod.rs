@@ -1,3 +1,4 @@
 pub mod defaults {
     pub(crate) const DEFAULT_FPS: u8 = 24;
+    pub(crate) const DEFAULT_FRAME_QUEUE_DEPTH: usize = 8;
 }
\ No newline at end of file